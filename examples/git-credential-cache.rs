@@ -0,0 +1,58 @@
+// Copyright 2019 Pascal Bach.
+//
+// SPDX-License-Identifier:	Apache-2.0 or MIT
+
+/// A git credential helper that keeps credentials in memory for a configurable number
+/// of seconds, backed by a small Unix-domain-socket daemon, rather than persisting them
+/// to disk.
+///
+/// Just copy the resulting binary `git-credential-cache` into `PATH` and configure it
+/// using `git config credential.helper "cache --timeout=300"`.
+#[cfg(unix)]
+fn main() {
+    use git_credential::{run, Cache};
+    use std::env;
+    use std::time::Duration;
+
+    let raw_args: Vec<String> = env::args().collect();
+
+    // Re-exec'd by `Cache` itself to run the background daemon.
+    if raw_args.get(1).map(String::as_str) == Some("--daemon") {
+        let socket_path = raw_args
+            .get(2)
+            .expect("--daemon requires a socket path argument");
+        let timeout: u64 = raw_args
+            .get(3)
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(900);
+        git_credential::run_daemon(socket_path.as_ref(), Duration::from_secs(timeout))
+            .expect("The credential cache daemon failed!");
+        return;
+    }
+
+    let mut timeout = 900;
+    let mut operation = None;
+    for arg in raw_args.iter().skip(1) {
+        if let Some(value) = arg.strip_prefix("--timeout=") {
+            timeout = value
+                .parse()
+                .expect("--timeout expects a number of seconds");
+        } else {
+            operation = Some(arg.clone());
+        }
+    }
+    let operation = operation.expect("Expected an operation (get/store/erase)");
+
+    let cache = Cache::new(Duration::from_secs(timeout));
+    let args = vec![raw_args[0].clone(), operation];
+    run(&cache, &args, std::io::stdin(), std::io::stdout())
+        .expect("Something went wrong running the helper!");
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!(
+        "git-credential-cache is only supported on Unix, where Unix-domain sockets are available."
+    );
+    std::process::exit(1);
+}
@@ -8,18 +8,24 @@
 ///
 /// Just copy the resulting binary `git-credential-env` into `PATH` and
 /// configure it using `git config credential.helper env`.
-use git_credential::GitCredential;
+use git_credential::{run, GitCredential, Helper, Result};
 use std::env;
 
-fn main() {
-    let mut gc = GitCredential::default();
-
-    // If we can't read a variable just ignore it.
-    gc.username = env::var("GIT_USER").ok();
-    gc.password = env::var("GIT_PASS").ok();
+struct Env;
 
-    let out = std::io::stdout();
+impl Helper for Env {
+    fn get(&self, mut ctx: GitCredential) -> Result<GitCredential> {
+        // If we can't read a variable just ignore it.
+        ctx.username = env::var("GIT_USER").ok();
+        ctx.password = env::var("GIT_PASS").ok();
+        Ok(ctx)
+    }
+    fn store(&self, _ctx: GitCredential) {}
+    fn erase(&self, _ctx: GitCredential) {}
+}
 
-    gc.to_writer(out)
-        .expect("Something went wrong writing the credentials!");
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    run(&Env, &args, std::io::stdin(), std::io::stdout())
+        .expect("Something went wrong running the helper!");
 }
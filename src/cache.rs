@@ -0,0 +1,416 @@
+// Copyright 2019 Pascal Bach.
+//
+// SPDX-License-Identifier:	Apache-2.0 or MIT
+
+//! Backs a `git-credential-cache` helper with a background Unix-domain-socket daemon
+//! that holds credentials in memory for a configurable number of seconds, instead of on
+//! disk. This mirrors git's own `credential-cache`/`credential-cache--daemon` design.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use snafu::ResultExt;
+
+use crate::{CacheError, GitCredential, Helper, Operation, Result};
+
+/// The key a cached credential is stored and looked up under.
+type Key = (String, String, String);
+
+struct Entry {
+    username: Option<String>,
+    password: Option<String>,
+    expires_at: Instant,
+}
+
+/// A `git-credential-cache` client: a [`Helper`](crate::Helper) that keeps credentials
+/// in memory behind a small daemon listening on a Unix socket, rather than persisting
+/// them to disk. The daemon is spawned on first use and outlives the client process.
+pub struct Cache {
+    socket_path: PathBuf,
+    timeout: Duration,
+}
+
+impl Cache {
+    /// Creates a client using the conventional per-user runtime socket path. `timeout`
+    /// is passed to the daemon when this client spawns it; a daemon that is already
+    /// running keeps the timeout it was started with.
+    pub fn new(timeout: Duration) -> Cache {
+        Cache {
+            socket_path: default_socket_path(),
+            timeout,
+        }
+    }
+
+    /// Creates a client using an explicit socket path. `timeout` is passed to the
+    /// daemon when this client spawns it; a daemon that is already running keeps the
+    /// timeout it was started with.
+    pub fn with_socket_path(socket_path: impl Into<PathBuf>, timeout: Duration) -> Cache {
+        Cache {
+            socket_path: socket_path.into(),
+            timeout,
+        }
+    }
+
+    /// Connects to the daemon, spawning it first if it is not already running.
+    fn ensure_daemon(&self) -> Result<()> {
+        if UnixStream::connect(&self.socket_path).is_ok() {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.socket_path.parent() {
+            fs::create_dir_all(parent).context(CacheError)?;
+            restrict_to_owner(parent)?;
+        }
+
+        let exe = env::current_exe().context(CacheError)?;
+        Command::new(exe)
+            .arg("--daemon")
+            .arg(&self.socket_path)
+            .arg(self.timeout.as_secs().to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context(CacheError)?;
+
+        for _ in 0..50 {
+            if UnixStream::connect(&self.socket_path).is_ok() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        Ok(())
+    }
+
+    /// Sends `ctx` to the daemon for `operation` and returns whatever it responds with.
+    fn request(&self, operation: Operation, ctx: &GitCredential) -> Result<GitCredential> {
+        self.ensure_daemon()?;
+        let mut stream = UnixStream::connect(&self.socket_path).context(CacheError)?;
+        writeln!(stream, "{}", operation.as_str()).context(CacheError)?;
+        ctx.to_writer(&mut stream)?;
+        GitCredential::from_reader(stream)
+    }
+}
+
+impl Helper for Cache {
+    fn get(&self, mut ctx: GitCredential) -> Result<GitCredential> {
+        let response = self.request(Operation::Get, &ctx)?;
+        if ctx.username.is_none() {
+            ctx.username = response.username;
+        }
+        if ctx.password.is_none() {
+            ctx.password = response.password;
+        }
+        Ok(ctx)
+    }
+
+    fn store(&self, ctx: GitCredential) {
+        if let Err(err) = self.request(Operation::Store, &ctx) {
+            warn!("Could not store credential in cache: {}", err);
+        }
+    }
+
+    fn erase(&self, ctx: GitCredential) {
+        if let Err(err) = self.request(Operation::Erase, &ctx) {
+            warn!("Could not erase credential from cache: {}", err);
+        }
+    }
+}
+
+/// Runs the cache daemon, listening on `socket_path` until the process is killed.
+///
+/// Entries are evicted once `timeout` has elapsed since they were stored. This is
+/// normally invoked by [`Cache`] itself, re-executing the `git-credential-cache` binary
+/// in the background; it is only `pub` so that binary can call into it.
+///
+/// Concurrent git invocations routinely race to spawn this daemon before any instance
+/// is listening yet. Only the first one to take the exclusive lock next to
+/// `socket_path` actually binds it; every other racer backs off and returns
+/// immediately, rather than clobbering the winner's socket and leaking itself as an
+/// orphaned, unreachable process.
+pub fn run_daemon(socket_path: &Path, timeout: Duration) -> Result<()> {
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent).context(CacheError)?;
+        restrict_to_owner(parent)?;
+    }
+
+    let lock_path = lock_path_for(socket_path);
+    let _lock = match try_lock_daemon(&lock_path)? {
+        Some(lock) => lock,
+        None => return Ok(()),
+    };
+
+    if socket_path.exists() {
+        fs::remove_file(socket_path).context(CacheError)?;
+    }
+
+    let listener = UnixListener::bind(socket_path).context(CacheError)?;
+    restrict_to_owner(socket_path)?;
+    let state: Arc<Mutex<HashMap<Key, Entry>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    {
+        let state = Arc::clone(&state);
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            let now = Instant::now();
+            state
+                .lock()
+                .unwrap()
+                .retain(|_, entry| entry.expires_at > now);
+        });
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    if let Err(err) = handle_connection(stream, &state, timeout) {
+                        warn!("git-credential-cache connection failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => warn!(
+                "git-credential-cache could not accept a connection: {}",
+                err
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn handle_connection(
+    mut stream: UnixStream,
+    state: &Mutex<HashMap<Key, Entry>>,
+    timeout: Duration,
+) -> Result<()> {
+    let writer = stream.try_clone().context(CacheError)?;
+    let mut reader = BufReader::new(&mut stream);
+
+    let mut operation_line = String::new();
+    reader.read_line(&mut operation_line).context(CacheError)?;
+    let operation = Operation::try_from(operation_line.trim_end())?;
+
+    let mut body = String::new();
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line).context(CacheError)?;
+        if read == 0 || line == "\n" || line == "\r\n" {
+            break;
+        }
+        body.push_str(&line);
+    }
+    let ctx = GitCredential::from_reader(body.as_bytes())?;
+    let key = key_for(&ctx);
+
+    let mut response = GitCredential::default();
+    match operation {
+        Operation::Get => {
+            let mut entries = state.lock().unwrap();
+            if let Some(entry) = entries.get(&key) {
+                if entry.expires_at > Instant::now() {
+                    response.username = entry.username.clone();
+                    response.password = entry.password.clone();
+                } else {
+                    entries.remove(&key);
+                }
+            }
+        }
+        Operation::Store => {
+            state.lock().unwrap().insert(
+                key,
+                Entry {
+                    username: ctx.username,
+                    password: ctx.password,
+                    expires_at: Instant::now() + timeout,
+                },
+            );
+        }
+        Operation::Erase => {
+            state.lock().unwrap().remove(&key);
+        }
+    }
+
+    response.to_writer(writer)
+}
+
+fn key_for(ctx: &GitCredential) -> Key {
+    (
+        ctx.protocol.clone().unwrap_or_default(),
+        ctx.host.clone().unwrap_or_default(),
+        ctx.path.clone().unwrap_or_default(),
+    )
+}
+
+/// Returns the real uid of the current process, for scoping fallback paths that are
+/// otherwise shared system-wide.
+fn current_uid() -> u32 {
+    extern "C" {
+        fn getuid() -> u32;
+    }
+    unsafe { getuid() }
+}
+
+/// Restricts `path` (a directory or a socket file) to the owner only, so the isolation
+/// the uid-namespaced fallback path is meant to give does not depend on the caller's
+/// umask happening to be restrictive.
+fn restrict_to_owner(path: &Path) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o700)).context(CacheError)?;
+    Ok(())
+}
+
+fn lock_path_for(socket_path: &Path) -> PathBuf {
+    let mut file_name = socket_path
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    file_name.push(".lock");
+    socket_path.with_file_name(file_name)
+}
+
+extern "C" {
+    fn flock(fd: i32, operation: i32) -> i32;
+}
+
+const LOCK_EX: i32 = 2;
+const LOCK_NB: i32 = 4;
+
+/// Takes an exclusive, non-blocking advisory lock on `lock_path`, so that of several
+/// processes racing to start the daemon, only one proceeds to remove and rebind the
+/// socket. Unlike a plain `O_EXCL` lockfile, `flock` is released by the kernel as soon
+/// as the holding process exits for any reason, so a daemon that crashes can never wedge
+/// future startups. The returned `File` must be kept alive for as long as the lock
+/// should be held.
+fn try_lock_daemon(lock_path: &Path) -> Result<Option<fs::File>> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)
+        .context(CacheError)?;
+    if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } == 0 {
+        Ok(Some(file))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Picks the socket path the same way `git-credential-cache--daemon` does: prefer a
+/// per-session directory under `XDG_RUNTIME_DIR`, since it is private to the user and
+/// cleaned up on logout. When that is unset (cron jobs, CI runners, containers without a
+/// login session), `env::temp_dir()` is shared by every user on the box, so the socket
+/// directory is namespaced by uid to stop one user's daemon from colliding with, or being
+/// readable by, another's.
+fn default_socket_path() -> PathBuf {
+    if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(runtime_dir)
+            .join("git-credential-cache")
+            .join("socket");
+    }
+    env::temp_dir()
+        .join(format!("git-credential-cache-{}", current_uid()))
+        .join("socket")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lock_path_for, run_daemon, Cache};
+    use crate::{GitCredential, Helper};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn stores_and_retrieves_a_credential() {
+        let socket_path = std::env::temp_dir().join(format!(
+            "git-credential-cache-test-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+
+        let daemon_socket = socket_path.clone();
+        thread::spawn(move || {
+            run_daemon(&daemon_socket, Duration::from_secs(60)).unwrap();
+        });
+        for _ in 0..50 {
+            if UnixStream::connect(&socket_path).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let cache = Cache::with_socket_path(socket_path.clone(), Duration::from_secs(60));
+
+        let mut stored = GitCredential::default();
+        stored.protocol = Some("https".into());
+        stored.host = Some("example.com".into());
+        stored.username = Some("me".into());
+        stored.password = Some("sekret".into());
+        cache.store(stored);
+
+        let mut query = GitCredential::default();
+        query.protocol = Some("https".into());
+        query.host = Some("example.com".into());
+        let result = cache.get(query).unwrap();
+        assert_eq!(result.username.unwrap(), "me");
+        assert_eq!(result.password.unwrap(), "sekret");
+
+        let _ = std::fs::remove_file(&socket_path);
+    }
+
+    #[test]
+    fn second_daemon_racer_backs_off_without_disturbing_the_first() {
+        let socket_path = std::env::temp_dir().join(format!(
+            "git-credential-cache-race-test-{}.sock",
+            std::process::id()
+        ));
+        let lock_path = lock_path_for(&socket_path);
+        let _ = std::fs::remove_file(&socket_path);
+        let _ = std::fs::remove_file(&lock_path);
+
+        let first_socket = socket_path.clone();
+        thread::spawn(move || {
+            run_daemon(&first_socket, Duration::from_secs(60)).unwrap();
+        });
+        for _ in 0..50 {
+            if UnixStream::connect(&socket_path).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let cache = Cache::with_socket_path(socket_path.clone(), Duration::from_secs(60));
+        let mut stored = GitCredential::default();
+        stored.protocol = Some("https".into());
+        stored.host = Some("example.com".into());
+        stored.username = Some("first".into());
+        cache.store(stored);
+
+        // A racer that loses the lock must back off and return immediately, without
+        // removing or rebinding the socket the first daemon already owns.
+        let second_socket = socket_path.clone();
+        let second = thread::spawn(move || run_daemon(&second_socket, Duration::from_secs(60)));
+        second.join().unwrap().unwrap();
+
+        let mut query = GitCredential::default();
+        query.protocol = Some("https".into());
+        query.host = Some("example.com".into());
+        let result = cache.get(query).unwrap();
+        assert_eq!(result.username.unwrap(), "first");
+
+        let _ = std::fs::remove_file(&socket_path);
+        let _ = std::fs::remove_file(&lock_path);
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2019 Pascal Bach.
+//
+// SPDX-License-Identifier:	Apache-2.0 or MIT
+
+//! Runs a sequence of external `git-credential-*` helper programs, the way git's own
+//! `credential.helper` configuration does.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use log::warn;
+use snafu::ResultExt;
+
+use crate::{GitCredential, Helper, Operation, Result, SpawnError};
+
+/// Runs an ordered list of external helper commands for `get`/`store`/`erase`, merging
+/// their responses the way git's `credential.helper` cascade does.
+///
+/// Each entry is resolved much like a `credential.helper` config value: a value
+/// starting with `!` is run through the shell, a value containing a path separator is
+/// run as-is, and any other name is resolved to `git-credential-<name>` on `PATH`.
+/// Outside the `!` form the whole entry is used as the program name; arguments after a
+/// space are not split off.
+pub struct Cascade {
+    helpers: Vec<String>,
+}
+
+impl Cascade {
+    /// Creates an empty cascade with no helpers configured.
+    pub fn new() -> Cascade {
+        Cascade {
+            helpers: Vec::new(),
+        }
+    }
+
+    /// Appends a helper, in the same format as a `credential.helper` config value.
+    pub fn push(&mut self, helper: impl Into<String>) -> &mut Cascade {
+        self.helpers.push(helper.into());
+        self
+    }
+
+    /// Builds a cascade preloaded with the conventional OS credential helper
+    /// (`osxkeychain` on macOS, `manager-core` on Windows, `libsecret` everywhere else).
+    pub fn platform_builtin() -> Cascade {
+        let mut cascade = Cascade::new();
+        if cfg!(target_os = "macos") {
+            cascade.push("osxkeychain");
+        } else if cfg!(target_os = "windows") {
+            cascade.push("manager-core");
+        } else {
+            cascade.push("libsecret");
+        }
+        cascade
+    }
+
+    /// Builds the command for `helper`, with `operation` already folded in as the final
+    /// argument. For `!`-prefixed shell snippets this must be appended to the shell string
+    /// itself rather than passed as an extra `sh -c script <arg>` operand: under POSIX `sh`
+    /// semantics that operand becomes `$0`, not `$1`, which is not what git's own `!shell`
+    /// helpers expect.
+    fn command_for(helper: &str, operation: Operation) -> Command {
+        if let Some(shell_snippet) = helper.strip_prefix('!') {
+            let mut command = Command::new("sh");
+            command
+                .arg("-c")
+                .arg(format!("{} {}", shell_snippet, operation.as_str()));
+            command
+        } else if helper.contains('/') || helper.contains('\\') {
+            let mut command = Command::new(helper);
+            command.arg(operation.as_str());
+            command
+        } else {
+            let mut command = Command::new(format!("git-credential-{}", helper));
+            command.arg(operation.as_str());
+            command
+        }
+    }
+
+    fn run_one(helper: &str, operation: Operation, ctx: &GitCredential) -> Result<GitCredential> {
+        let mut child = Self::command_for(helper, operation)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context(SpawnError {
+                helper: helper.to_string(),
+            })?;
+
+        ctx.to_writer(child.stdin.take().expect("stdin is piped"))?;
+
+        let output = child.wait_with_output().context(SpawnError {
+            helper: helper.to_string(),
+        })?;
+        GitCredential::from_reader(output.stdout.as_slice())
+    }
+}
+
+impl Default for Cascade {
+    fn default() -> Cascade {
+        Cascade::new()
+    }
+}
+
+impl Helper for Cascade {
+    fn get(&self, mut ctx: GitCredential) -> Result<GitCredential> {
+        for helper in &self.helpers {
+            match Self::run_one(helper, Operation::Get, &ctx) {
+                Ok(response) => merge(&mut ctx, response),
+                Err(source) => warn!("Helper '{}' failed: {}", helper, source),
+            }
+            if ctx.username.is_some() && ctx.password.is_some() {
+                break;
+            }
+        }
+        Ok(ctx)
+    }
+
+    fn store(&self, ctx: GitCredential) {
+        for helper in &self.helpers {
+            if let Err(source) = Self::run_one(helper, Operation::Store, &ctx) {
+                warn!("Helper '{}' failed: {}", helper, source);
+            }
+        }
+    }
+
+    fn erase(&self, ctx: GitCredential) {
+        for helper in &self.helpers {
+            if let Err(source) = Self::run_one(helper, Operation::Erase, &ctx) {
+                warn!("Helper '{}' failed: {}", helper, source);
+            }
+        }
+    }
+}
+
+/// Fills every field still unset in `target` from `source`, so earlier helpers in the
+/// cascade always win over later ones.
+fn merge(target: &mut GitCredential, source: GitCredential) {
+    if target.url.is_none() {
+        target.url = source.url;
+    }
+    if target.protocol.is_none() {
+        target.protocol = source.protocol;
+    }
+    if target.host.is_none() {
+        target.host = source.host;
+    }
+    if target.path.is_none() {
+        target.path = source.path;
+    }
+    if target.username.is_none() {
+        target.username = source.username;
+    }
+    if target.password.is_none() {
+        target.password = source.password;
+    }
+    if target.password_expiry_utc.is_none() {
+        target.password_expiry_utc = source.password_expiry_utc;
+    }
+    if target.oauth_refresh_token.is_none() {
+        target.oauth_refresh_token = source.oauth_refresh_token;
+    }
+    if target.authtype.is_none() {
+        target.authtype = source.authtype;
+    }
+    if target.credential.is_none() {
+        target.credential = source.credential;
+    }
+    if target.wwwauth.is_empty() {
+        target.wwwauth = source.wwwauth;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, Cascade};
+    use crate::{GitCredential, Operation};
+
+    #[test]
+    fn merge_fills_only_unset_fields() {
+        let mut target = GitCredential::default();
+        target.username = Some("me".into());
+
+        let mut source = GitCredential::default();
+        source.username = Some("other".into());
+        source.password = Some("sekret".into());
+
+        merge(&mut target, source);
+
+        assert_eq!(target.username.unwrap(), "me");
+        assert_eq!(target.password.unwrap(), "sekret");
+    }
+
+    #[test]
+    fn shell_helper_sees_operation_as_first_positional() {
+        let ctx = GitCredential::default();
+        let response =
+            Cascade::run_one("!f() { echo \"username=$1\"; }; f", Operation::Get, &ctx).unwrap();
+
+        assert_eq!(response.username.unwrap(), "get");
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright 2019 Pascal Bach.
+//
+// SPDX-License-Identifier:	Apache-2.0 or MIT
+
+//! Dispatches the `get`/`store`/`erase` operations git passes to a credential helper on
+//! the command line, so a binary only has to implement the [`Helper`] trait.
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+use snafu::OptionExt;
+
+use crate::{GitCredential, MissingOperation, Result, UnknownOperation};
+
+/// The operation git requests via `argv[1]` when invoking a credential helper.
+///
+/// See [gitcredentials[7]](https://git-scm.com/docs/gitcredentials#_custom_helpers) for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Git wants a credential; the helper should return one on stdout.
+    Get,
+    /// Git is telling the helper that a credential was used successfully.
+    Store,
+    /// Git is telling the helper that a credential was rejected.
+    Erase,
+}
+
+impl Operation {
+    /// The spelling git uses for this operation as `argv[1]`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Get => "get",
+            Operation::Store => "store",
+            Operation::Erase => "erase",
+        }
+    }
+}
+
+impl TryFrom<&str> for Operation {
+    type Error = crate::Error;
+
+    fn try_from(value: &str) -> Result<Operation> {
+        match value {
+            "get" => Ok(Operation::Get),
+            "store" => Ok(Operation::Store),
+            "erase" => Ok(Operation::Erase),
+            _ => UnknownOperation {
+                operation: value.to_string(),
+            }
+            .fail(),
+        }
+    }
+}
+
+/// Implements the behaviour of a git credential helper.
+///
+/// A type implementing `Helper` only has to provide the logic for each operation;
+/// reading the request from stdin, dispatching on `argv[1]` and writing the response
+/// to stdout is handled by [`run`].
+pub trait Helper {
+    /// Called for the `get` operation. Returns `ctx` filled in with the credential,
+    /// which is then written back to the caller.
+    fn get(&self, ctx: GitCredential) -> Result<GitCredential>;
+    /// Called for the `store` operation. `ctx` holds the credential that was used
+    /// successfully and should be persisted.
+    fn store(&self, ctx: GitCredential);
+    /// Called for the `erase` operation. `ctx` holds the credential that was
+    /// rejected and should be forgotten.
+    fn erase(&self, ctx: GitCredential);
+}
+
+/// Reads a [`GitCredential`] from `stdin`, dispatches it to `helper` according to the
+/// operation named in `args[1]`, and for `get` writes the resulting credential to
+/// `stdout`.
+///
+/// `args` is expected to be the process arguments, i.e. `args[0]` is the program name
+/// and `args[1]` the operation (`get`, `store` or `erase`), matching `std::env::args`.
+pub fn run<H: Helper>(
+    helper: &H,
+    args: &[String],
+    stdin: impl Read,
+    stdout: impl Write,
+) -> Result<()> {
+    let operation = args.get(1).map(String::as_str).context(MissingOperation)?;
+    let operation = Operation::try_from(operation)?;
+
+    let ctx = GitCredential::from_reader(stdin)?;
+
+    match operation {
+        Operation::Get => {
+            let ctx = helper.get(ctx)?;
+            ctx.to_writer(stdout)?;
+        }
+        Operation::Store => helper.store(ctx),
+        Operation::Erase => helper.erase(ctx),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, GitCredential, Helper, Operation};
+    use std::convert::TryFrom;
+
+    struct EchoHelper;
+
+    impl Helper for EchoHelper {
+        fn get(&self, mut ctx: GitCredential) -> crate::Result<GitCredential> {
+            ctx.username = Some("me".into());
+            Ok(ctx)
+        }
+        fn store(&self, _ctx: GitCredential) {}
+        fn erase(&self, _ctx: GitCredential) {}
+    }
+
+    #[test]
+    fn parses_known_operations() {
+        assert_eq!(Operation::try_from("get").unwrap(), Operation::Get);
+        assert_eq!(Operation::try_from("store").unwrap(), Operation::Store);
+        assert_eq!(Operation::try_from("erase").unwrap(), Operation::Erase);
+        assert!(Operation::try_from("frobnicate").is_err());
+    }
+
+    #[test]
+    fn run_dispatches_get() {
+        let args = vec!["git-credential-echo".to_string(), "get".to_string()];
+        let input = "protocol=https\nhost=example.com\n\n".as_bytes();
+        let mut output: Vec<u8> = Vec::new();
+        run(&EchoHelper, &args, input, &mut output).unwrap();
+        assert_eq!(
+            "protocol=https\nhost=example.com\nusername=me\n\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn run_requires_an_operation() {
+        let args = vec!["git-credential-echo".to_string()];
+        let input = "\n".as_bytes();
+        let mut output: Vec<u8> = Vec::new();
+        assert!(run(&EchoHelper, &args, input, &mut output).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2019 Pascal Bach.
+//
+// SPDX-License-Identifier:	Apache-2.0 or MIT
+
+//! An optional interactive fallback, enabled with the `interactive` cargo feature, that
+//! prompts the terminal for any `username`/`password` a wrapped helper could not fill in.
+
+use std::io::{self, Write};
+
+use snafu::ResultExt;
+
+use crate::{GitCredential, Helper, ReadError, Result, WriteError};
+
+/// Wraps a [`Helper`](crate::Helper) and, during `get`, falls back to prompting the
+/// terminal for `username`/`password` fields that are still missing after delegating to
+/// the wrapped helper.
+pub struct Interactive<H> {
+    inner: H,
+}
+
+impl<H: Helper> Interactive<H> {
+    /// Wraps `inner`, adding an interactive fallback for missing fields.
+    pub fn new(inner: H) -> Interactive<H> {
+        Interactive { inner }
+    }
+}
+
+impl<H: Helper> Helper for Interactive<H> {
+    fn get(&self, ctx: GitCredential) -> Result<GitCredential> {
+        let mut ctx = self.inner.get(ctx)?;
+        if ctx.username.is_none() {
+            ctx.username = Some(prompt(&ctx.to_prompt("Username"))?);
+        }
+        if ctx.password.is_none() {
+            ctx.password = Some(prompt_password(&ctx.to_prompt("Password"))?);
+        }
+        Ok(ctx)
+    }
+
+    fn store(&self, ctx: GitCredential) {
+        self.inner.store(ctx)
+    }
+
+    fn erase(&self, ctx: GitCredential) {
+        self.inner.erase(ctx)
+    }
+}
+
+/// Prints `message` and reads a line of input from the terminal.
+fn prompt(message: &str) -> Result<String> {
+    print!("{}", message);
+    io::stdout().flush().context(WriteError)?;
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).context(ReadError {})?;
+    Ok(line
+        .trim_end_matches(|c| c == '\n' || c == '\r')
+        .to_string())
+}
+
+/// Prints `message` and reads a password from the terminal without echoing it.
+fn prompt_password(message: &str) -> Result<String> {
+    rpassword::prompt_password_stdout(message).context(ReadError {})
+}
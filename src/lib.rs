@@ -17,6 +17,19 @@ use url::Url;
 
 use snafu::{ResultExt, Snafu};
 
+#[cfg(unix)]
+mod cache;
+mod cascade;
+mod helper;
+#[cfg(feature = "interactive")]
+mod interactive;
+#[cfg(unix)]
+pub use cache::{run_daemon, Cache};
+pub use cascade::Cascade;
+pub use helper::{run, Helper, Operation};
+#[cfg(feature = "interactive")]
+pub use interactive::Interactive;
+
 /// Errors that can occur while reading or writing the git credential format
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -40,6 +53,39 @@ pub enum Error {
         /// The underlying io error causing the issue
         source: url::ParseError,
     },
+    /// Indicates that git invoked the helper without an operation on the command line.
+    #[snafu(display("Missing operation, expected one of get/store/erase as argv[1]"))]
+    MissingOperation,
+    /// Indicates that git invoked the helper with an operation other than get/store/erase.
+    #[snafu(display("Unknown operation: {}", operation))]
+    UnknownOperation {
+        /// The unrecognized operation that was passed on the command line
+        operation: String,
+    },
+    /// Indicates that a key or value contained a newline or NUL byte, which would corrupt the
+    /// line-oriented git-credential protocol.
+    #[snafu(display("Key or value contains a newline or NUL byte: {}={}", key, value))]
+    EncodingError {
+        /// The key that was about to be written
+        key: String,
+        /// The value that contained a newline or NUL byte
+        value: String,
+    },
+    /// Indicates that an external helper program could not be spawned or communicated with.
+    #[snafu(display("Could not run helper '{}': {}", helper, source))]
+    SpawnError {
+        /// The helper command that failed
+        helper: String,
+        /// The underlying io error causing the issue
+        source: std::io::Error,
+    },
+    /// Indicates that the `git-credential-cache` daemon could not be started or
+    /// communicated with over its Unix socket.
+    #[snafu(display("Could not talk to the credential cache daemon: {}", source))]
+    CacheError {
+        /// The underlying io error causing the issue
+        source: std::io::Error,
+    },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -62,6 +108,18 @@ pub struct GitCredential {
     pub username: Option<String>,
     /// The credential’s password, if we are asking it to be stored.
     pub password: Option<String>,
+    /// The time (in seconds since the UNIX epoch) at which the password will expire, if known.
+    pub password_expiry_utc: Option<u64>,
+    /// The OAuth refresh token, if the credential was obtained via OAuth and supports refresh.
+    pub oauth_refresh_token: Option<String>,
+    /// The type of authentication that `credential` should be used with (e.g. `Bearer`), for
+    /// non-basic authentication schemes.
+    pub authtype: Option<String>,
+    /// The credential to present for a non-basic `authtype` (e.g. a bearer token).
+    pub credential: Option<String>,
+    /// One entry per `WWW-Authenticate` header seen on the most recent response, in the order
+    /// git emitted them.
+    pub wwwauth: Vec<String>,
 }
 
 impl Default for GitCredential {
@@ -74,6 +132,11 @@ impl Default for GitCredential {
             path: None,
             username: None,
             password: None,
+            password_expiry_utc: None,
+            oauth_refresh_token: None,
+            authtype: None,
+            credential: None,
+            wwwauth: Vec::new(),
         }
     }
 }
@@ -101,12 +164,11 @@ impl GitCredential {
                 // TODO: Make sure an empty line exists in the end
                 break;
             }
-            match line.split_terminator('=').collect::<Vec<&str>>().as_slice() {
+            match line.splitn(2, '=').collect::<Vec<&str>>().as_slice() {
                 [key, value] => {
                     debug!("Reading line with: {} = {}", key, value);
                     let value = (*value).to_string();
-                    let key = key.to_owned(); // TODO: Why is this needed?
-                    match key {
+                    match *key {
                         "url" => {
                             gc.url = {
                                 let value = Url::parse(&value).context(ParseError { value })?;
@@ -118,6 +180,14 @@ impl GitCredential {
                         "path" => gc.path = Some(value),
                         "username" => gc.username = Some(value),
                         "password" => gc.password = Some(value),
+                        "password_expiry_utc" => match value.parse() {
+                            Ok(expiry) => gc.password_expiry_utc = Some(expiry),
+                            Err(_) => warn!("Invalid password_expiry_utc value: {}", &value),
+                        },
+                        "oauth_refresh_token" => gc.oauth_refresh_token = Some(value),
+                        "authtype" => gc.authtype = Some(value),
+                        "credential" => gc.credential = Some(value),
+                        "wwwauth[]" => gc.wwwauth.push(value),
                         _ => warn!("Unknown key: {} = {}", &key, &value),
                     };
                 }
@@ -143,31 +213,154 @@ impl GitCredential {
     /// assert_eq!("username=me\npassword=%sec&ret!\n\n", String::from_utf8(v).unwrap());
     /// ```
     pub fn to_writer(&self, mut sink: impl Write) -> Result<()> {
+        // Validate every field up front so a late encoding error can never leave a
+        // truncated, non-terminated record already written to the sink: callers now
+        // routinely hand us a live pipe (a cascade child's stdin, the cache daemon
+        // socket), where a partial write is a malformed payload downstream, not just a
+        // cosmetic issue.
+        if let Some(protocol) = &self.protocol {
+            check_encoding("protocol", protocol)?;
+        }
+        if let Some(host) = &self.host {
+            check_encoding("host", host)?;
+        }
+        if let Some(path) = &self.path {
+            check_encoding("path", path)?;
+        }
+        if let Some(username) = &self.username {
+            check_encoding("username", username)?;
+        }
+        if let Some(password) = &self.password {
+            check_encoding("password", password)?;
+        }
+        if let Some(oauth_refresh_token) = &self.oauth_refresh_token {
+            check_encoding("oauth_refresh_token", oauth_refresh_token)?;
+        }
+        if let Some(authtype) = &self.authtype {
+            check_encoding("authtype", authtype)?;
+        }
+        if let Some(credential) = &self.credential {
+            check_encoding("credential", credential)?;
+        }
+        for wwwauth in &self.wwwauth {
+            check_encoding("wwwauth[]", wwwauth)?;
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+
         // The url filed is written first, this allows the other fields to override
         // parts of the url
         if let Some(url) = &self.url {
-            writeln!(sink, "url={}", url).context(WriteError)?;
+            writeln!(buf, "url={}", url).context(WriteError)?;
         }
         if let Some(protocol) = &self.protocol {
-            writeln!(sink, "protocol={}", protocol).context(WriteError)?;
+            writeln!(buf, "protocol={}", protocol).context(WriteError)?;
         }
         if let Some(host) = &self.host {
-            writeln!(sink, "host={}", host).context(WriteError)?;
+            writeln!(buf, "host={}", host).context(WriteError)?;
         }
         if let Some(path) = &self.path {
-            writeln!(sink, "path={}", path).context(WriteError)?;
+            writeln!(buf, "path={}", path).context(WriteError)?;
         }
         if let Some(username) = &self.username {
-            writeln!(sink, "username={}", username).context(WriteError)?;
+            writeln!(buf, "username={}", username).context(WriteError)?;
         }
         if let Some(password) = &self.password {
-            writeln!(sink, "password={}", password).context(WriteError)?;
+            writeln!(buf, "password={}", password).context(WriteError)?;
+        }
+        if let Some(password_expiry_utc) = &self.password_expiry_utc {
+            writeln!(buf, "password_expiry_utc={}", password_expiry_utc).context(WriteError)?;
+        }
+        if let Some(oauth_refresh_token) = &self.oauth_refresh_token {
+            writeln!(buf, "oauth_refresh_token={}", oauth_refresh_token).context(WriteError)?;
+        }
+        if let Some(authtype) = &self.authtype {
+            writeln!(buf, "authtype={}", authtype).context(WriteError)?;
+        }
+        if let Some(credential) = &self.credential {
+            writeln!(buf, "credential={}", credential).context(WriteError)?;
+        }
+        for wwwauth in &self.wwwauth {
+            writeln!(buf, "wwwauth[]={}", wwwauth).context(WriteError)?;
         }
 
         // One empty line in the end
-        writeln!(sink).context(WriteError)?;
+        writeln!(buf).context(WriteError)?;
+
+        sink.write_all(&buf).context(WriteError)?;
         Ok(())
     }
+
+    /// Reconstructs a `<protocol>://<user>@<host>/<path>` URL from the individual
+    /// fields, skipping any that are absent. Returns `None` if `protocol` or `host`
+    /// is missing, since neither scheme nor authority could be formed without them.
+    ///
+    /// ```
+    /// use git_credential::GitCredential;
+    ///
+    /// let mut g = GitCredential::default();
+    /// g.protocol = Some("https".into());
+    /// g.host = Some("example.com".into());
+    /// g.username = Some("me".into());
+    /// g.path = Some("myproject.git".into());
+    ///
+    /// assert_eq!(g.to_url().unwrap(), "https://me@example.com/myproject.git");
+    /// ```
+    pub fn to_url(&self) -> Option<String> {
+        let protocol = self.protocol.as_ref()?;
+        let host = self.host.as_ref()?;
+
+        let mut url = format!("{}://", protocol);
+        if let Some(username) = &self.username {
+            url.push_str(username);
+            url.push('@');
+        }
+        url.push_str(host);
+        if let Some(path) = &self.path {
+            if !path.starts_with('/') {
+                url.push('/');
+            }
+            url.push_str(path);
+        }
+        Some(url)
+    }
+
+    /// Builds a prompt for an interactive fallback, e.g.
+    /// `"Password for https://me@example.com: "`. `field` is the label to use, such as
+    /// `"Username"` or `"Password"`.
+    ///
+    /// ```
+    /// use git_credential::GitCredential;
+    ///
+    /// let mut g = GitCredential::default();
+    /// g.protocol = Some("https".into());
+    /// g.host = Some("example.com".into());
+    /// g.username = Some("me".into());
+    ///
+    /// assert_eq!(g.to_prompt("Password"), "Password for https://me@example.com: ");
+    /// ```
+    pub fn to_prompt(&self, field: &str) -> String {
+        match self.to_url() {
+            Some(url) => format!("{} for {}: ", field, url),
+            None => format!("{}: ", field),
+        }
+    }
+}
+
+/// Rejects keys or values containing a newline or NUL byte, which would corrupt the
+/// line-oriented git-credential protocol.
+fn check_encoding(key: &str, value: &str) -> Result<()> {
+    if [key, value]
+        .iter()
+        .any(|field| field.contains('\n') || field.contains('\0'))
+    {
+        return EncodingError {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+        .fail();
+    }
+    Ok(())
 }
 
 // Make sure the readme is tested too
@@ -214,4 +407,82 @@ mod tests {
         g.to_writer(&mut v).unwrap();
         assert_eq!(s, String::from_utf8(v).unwrap());
     }
+
+    #[test]
+    fn to_url_skips_absent_fields() {
+        let mut g = GitCredential::default();
+        g.protocol = Some("https".into());
+        g.host = Some("example.com".into());
+        assert_eq!(g.to_url().unwrap(), "https://example.com");
+    }
+
+    #[test]
+    fn to_url_is_none_without_protocol_or_host() {
+        let g = GitCredential::default();
+        assert!(g.to_url().is_none());
+    }
+
+    #[test]
+    fn to_prompt_falls_back_without_a_url() {
+        let g = GitCredential::default();
+        assert_eq!(g.to_prompt("Username"), "Username: ");
+    }
+
+    #[test]
+    fn read_value_containing_equals_sign() {
+        let s = "password=AAAA====\n\n".as_bytes();
+        let g = GitCredential::from_reader(s).unwrap();
+        assert_eq!(g.password.unwrap(), "AAAA====");
+    }
+
+    #[test]
+    fn write_rejects_newline_in_value() {
+        let mut g = GitCredential::default();
+        g.password = Some("sec\nret".into());
+        let mut v: Vec<u8> = Vec::new();
+        assert!(g.to_writer(&mut v).is_err());
+    }
+
+    #[test]
+    fn write_rejects_nul_in_value() {
+        let mut g = GitCredential::default();
+        g.password = Some("sec\0ret".into());
+        let mut v: Vec<u8> = Vec::new();
+        assert!(g.to_writer(&mut v).is_err());
+    }
+
+    #[test]
+    fn write_does_not_emit_partial_record_when_a_later_field_is_invalid() {
+        let mut g = GitCredential::default();
+        g.protocol = Some("https".into());
+        g.password = Some("sec\nret".into());
+        let mut v: Vec<u8> = Vec::new();
+        assert!(g.to_writer(&mut v).is_err());
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn read_extended_attributes() {
+        let s = "password_expiry_utc=1234567890\noauth_refresh_token=r1\nauthtype=Bearer\ncredential=token\nwwwauth[]=Basic\nwwwauth[]=Bearer\n\n";
+        let g = GitCredential::from_reader(s.as_bytes()).unwrap();
+        assert_eq!(g.password_expiry_utc.unwrap(), 1_234_567_890);
+        assert_eq!(g.oauth_refresh_token.unwrap(), "r1");
+        assert_eq!(g.authtype.unwrap(), "Bearer");
+        assert_eq!(g.credential.unwrap(), "token");
+        assert_eq!(g.wwwauth, vec!["Basic".to_string(), "Bearer".to_string()]);
+    }
+
+    #[test]
+    fn write_extended_attributes() {
+        let s = "password_expiry_utc=1234567890\noauth_refresh_token=r1\nauthtype=Bearer\ncredential=token\nwwwauth[]=Basic\nwwwauth[]=Bearer\n\n";
+        let mut g = GitCredential::default();
+        g.password_expiry_utc = Some(1_234_567_890);
+        g.oauth_refresh_token = Some("r1".into());
+        g.authtype = Some("Bearer".into());
+        g.credential = Some("token".into());
+        g.wwwauth = vec!["Basic".into(), "Bearer".into()];
+        let mut v: Vec<u8> = Vec::new();
+        g.to_writer(&mut v).unwrap();
+        assert_eq!(s, String::from_utf8(v).unwrap());
+    }
 }